@@ -7,10 +7,19 @@ use std::string::*;
 mod sim;
 use sim::*;
 
-fn writes_to_memory(trace: String, algorithm: String) -> Vec<(u32, u32)> {
+// number of translations cached in each simulated TLB
+const TLB_CAPACITY: usize = 64;
+
+// operations each process runs before the round-robin scheduler switches
+const QUANTUM: u32 = 1000;
+
+// operations between timer ticks that clear reference bits (for NRU)
+const TICK_INTERVAL: u32 = 10_000;
+
+fn writes_to_memory(trace: String, algorithm: String) -> Vec<(u32, SimState)> {
     // start with some guess
     // if a write occurs, abort, and restart with more pages
-    let mut entries: Vec<(u32, u32)> = Vec::new();
+    let mut entries: Vec<(u32, SimState)> = Vec::new();
     let mut n_pages = 0;
 
     loop {
@@ -20,15 +29,17 @@ fn writes_to_memory(trace: String, algorithm: String) -> Vec<(u32, u32)> {
             algorithm.as_str().to_string(),
             trace.as_str().to_string(),
             false,
+            TLB_CAPACITY,
         );
 
         println!("  testing with {} pages", n_pages);
 
-        let last = sim.last().unwrap() as SimState;
+        let last = sim.last().unwrap();
 
-        entries.push((n_pages, last.write_count));
+        let done = last.write_count == 0;
+        entries.push((n_pages, last));
 
-        if last.write_count == 0 {
+        if done {
             return entries;
         }
     }
@@ -54,9 +65,13 @@ fn get_data(trace: String, algorithm: &String) -> Result<()> {
     let entries = writes_to_memory(trace.to_string(), algorithm.to_string());
 
     // header
-    writeln!(file, "\"pages\",\"writes\"")?;
-    for (pages, writes) in entries {
-        writeln!(file, "{},{}", pages, writes)?;
+    writeln!(file, "\"pages\",\"writes\",\"tlb_hits\",\"tlb_misses\"")?;
+    for (pages, state) in entries {
+        writeln!(
+            file,
+            "{},{},{},{}",
+            pages, state.write_count, state.tlb_hits, state.tlb_misses
+        )?;
     }
 
     println!(
@@ -81,6 +96,7 @@ fn find_optimal_memory(trace: String, algorithm: String) -> u32 {
             algorithm.as_str().to_string(),
             trace.as_str().to_string(),
             false,
+            TLB_CAPACITY,
         );
 
         for state in sim {
@@ -141,7 +157,7 @@ fn main() -> Result<()> {
         "traces/sixpack.trace",
         "traces/swim.trace",
     ];
-    let algorithms = vec!["lru", "fifo", "random"];
+    let algorithms = vec!["lru", "fifo", "random", "clock", "nru", "opt"];
 
     let args = env::args().collect::<Vec<String>>();
     let mode = args.get(1).unwrap().as_str();
@@ -160,6 +176,39 @@ fn main() -> Result<()> {
         return Ok(());
     }
 
+    if mode == "multi" {
+        // run every trace at once, contending for a shared physical memory
+        let n_frames: u32 = match args.get(2).and_then(|a| a.parse().ok()) {
+            Some(n) => n,
+            None => panic!("Expected arguments to be in format multi <nframes> <random|lru|fifo|clock|nru|opt>"),
+        };
+        let algorithm = args.get(3).map(|a| a.to_owned()).unwrap_or("lru".to_string());
+
+        let state = Sim::new_multi(
+            n_frames,
+            algorithm.to_string(),
+            traces.iter().map(|t| t.to_string()).collect(),
+            false,
+            TLB_CAPACITY,
+            QUANTUM,
+            TICK_INTERVAL,
+        )
+        .last()
+        .unwrap();
+
+        println!(
+            "shared memory frames: {}\nalgorithm:            {}\nevents in trace:      {}\ntotal disk reads:     {}\ntotal disk writes:    {}",
+            n_frames, algorithm, state.total_events, state.read_count, state.write_count
+        );
+        for (pid, stats) in state.per_process.iter().enumerate() {
+            println!(
+                "  process {} ({}): reads {} writes {} faults {}",
+                pid, traces[pid], stats.read_count, stats.write_count, stats.fault_count
+            );
+        }
+        return Ok(());
+    }
+
     if mode == "data" {
         let out_dir = path::Path::new("out");
         if !out_dir.exists() {
@@ -185,13 +234,14 @@ fn main() -> Result<()> {
 
     let (n_frames, algorithm, debug, trace_file) = get_args()?;
 
-    let state = Sim::new(n_frames, algorithm, trace_file, debug)
+    let state = Sim::new(n_frames, algorithm, trace_file, debug, TLB_CAPACITY)
         .last()
         .unwrap();
 
     println!(
-        "total memory frames: {}\nevents in trace:     {}\ntotal disk reads:    {}\ntotal disk writes:   {}",
-        n_frames, state.total_events, state.read_count, state.write_count
+        "total memory frames: {}\nevents in trace:     {}\ntotal disk reads:    {}\ntotal disk writes:   {}\nprotection faults:   {}\ntlb hits:            {}\ntlb misses:          {}",
+        n_frames, state.total_events, state.read_count, state.write_count,
+        state.protection_fault_count, state.tlb_hits, state.tlb_misses
     );
 
     return Ok(());
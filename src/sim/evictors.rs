@@ -3,6 +3,8 @@ use std::collections::HashMap;
 use std::fmt;
 use std::time::{SystemTime, UNIX_EPOCH};
 
+use super::Process;
+
 fn now() -> u128 {
     SystemTime::now()
         .duration_since(UNIX_EPOCH)
@@ -10,9 +12,21 @@ fn now() -> u128 {
         .as_millis()
 }
 
+// per-page permission bits, mirroring the RISC-V MMU flag layout
+pub const MMUFLAG_READABLE: u8 = 1 << 0;
+pub const MMUFLAG_WRITABLE: u8 = 1 << 1;
+pub const MMUFLAG_EXECUTABLE: u8 = 1 << 2;
+pub const MMUFLAG_USERMODE: u8 = 1 << 3;
+
 #[derive(Debug)]
 pub struct PageTableEntry {
     pub is_dirty: bool,
+    // mirrors the hardware ACCESSED bit: set on every reference, cleared by
+    // the clock hand as it sweeps looking for a victim and, under nru, on
+    // every timer tick
+    pub referenced: bool,
+    // permission bits: a mask of the MMUFLAG_* constants
+    pub flags: u8,
     pub created_at: u128,
     pub last_referenced: u128,
 }
@@ -20,31 +34,97 @@ impl PageTableEntry {
     pub fn new() -> Self {
         return Self {
             is_dirty: false,
+            referenced: false,
+            // default to a user page that is both readable and writable, so
+            // unannotated traces behave exactly as before
+            flags: MMUFLAG_READABLE | MMUFLAG_WRITABLE | MMUFLAG_USERMODE,
             created_at: now(),
             last_referenced: now(),
         };
     }
     pub fn reference(&mut self) {
+        self.referenced = true;
         self.last_referenced = now();
     }
+    // returns true when the page carries the given permission bit
+    pub fn permits(&self, flag: u8) -> bool {
+        return self.flags & flag != 0;
+    }
 }
 
 impl fmt::Display for PageTableEntry {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         return write!(
             f,
-            "PageTableEntry {{ is_dirty: {} created_at: {:?} last_referenced {:?} }}",
-            self.is_dirty, self.created_at, self.last_referenced
+            "PageTableEntry {{ is_dirty: {} referenced: {} flags: {:#06b} created_at: {:?} last_referenced {:?} }}",
+            self.is_dirty, self.referenced, self.flags, self.created_at, self.last_referenced
         );
     }
 }
 
-pub type Memory = Vec<Option<u32>>;
-pub type PageTable = HashMap<u32, PageTableEntry>;
+// a resident frame records both the page it holds and the process that owns it,
+// so eviction never hands a victim's frame back to another process's mapping
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Frame {
+    pub pid: u32,
+    pub vpn: u32,
+}
+pub type Memory = Vec<Option<Frame>>;
+// sparse two-level page table: the 20-bit virtual page number splits into a
+// 10-bit top index selecting a second-level table and a 10-bit index into it
+pub type PageTable = HashMap<u32, HashMap<u32, PageTableEntry>>;
+
+// high 10 bits of the 20-bit virtual page number
+fn top_index(virtual_page_number: u32) -> u32 {
+    return virtual_page_number >> 10;
+}
+// low 10 bits of the 20-bit virtual page number
+fn second_index(virtual_page_number: u32) -> u32 {
+    return virtual_page_number & 0x3FF;
+}
+
+// walk the two levels to the entry for a page, if present
+pub fn pte_get(page_table: &PageTable, virtual_page_number: u32) -> Option<&PageTableEntry> {
+    return page_table
+        .get(&top_index(virtual_page_number))
+        .and_then(|level| level.get(&second_index(virtual_page_number)));
+}
+
+pub fn pte_get_mut(
+    page_table: &mut PageTable,
+    virtual_page_number: u32,
+) -> Option<&mut PageTableEntry> {
+    return page_table
+        .get_mut(&top_index(virtual_page_number))
+        .and_then(|level| level.get_mut(&second_index(virtual_page_number)));
+}
+
+pub fn pte_contains(page_table: &PageTable, virtual_page_number: u32) -> bool {
+    return pte_get(page_table, virtual_page_number).is_some();
+}
+
+// create the entry (and its second-level table) if it does not already exist
+pub fn pte_insert(page_table: &mut PageTable, virtual_page_number: u32) {
+    page_table
+        .entry(top_index(virtual_page_number))
+        .or_insert_with(HashMap::new)
+        .entry(second_index(virtual_page_number))
+        .or_insert_with(PageTableEntry::new);
+}
+
+// drop the entry, pruning an emptied second-level table
+pub fn pte_remove(page_table: &mut PageTable, virtual_page_number: u32) {
+    if let Some(level) = page_table.get_mut(&top_index(virtual_page_number)) {
+        level.remove(&second_index(virtual_page_number));
+        if level.is_empty() {
+            page_table.remove(&top_index(virtual_page_number));
+        }
+    }
+}
 
 // returns Some(index) for the first empty slot, or None if memory is full
 fn get_first_empty_index(memory: &Memory) -> Option<usize> {
-    return memory.iter().position(|el: &Option<u32>| el.is_none());
+    return memory.iter().position(|el: &Option<Frame>| el.is_none());
 }
 
 // pick a memory address to evict
@@ -52,22 +132,30 @@ fn evict_random(memory: &Memory) -> usize {
     return rand::thread_rng().gen_range(0, memory.len());
 }
 
+// resolve the entry of the frame resident in a slot through its owning process's
+// page table
+fn frame_entry<'a>(processes: &'a [Process], frame: &Frame) -> Option<&'a PageTableEntry> {
+    return processes
+        .get(frame.pid as usize)
+        .and_then(|process| pte_get(&process.page_table, frame.vpn));
+}
+
 fn memory_to_pages<'a>(
     memory: &Memory,
-    page_table: &'a PageTable,
+    processes: &'a [Process],
 ) -> Vec<(usize, &'a PageTableEntry)> {
     return memory
         .iter()
         .enumerate()
-        .filter_map(|(index, maybe_page): (usize, &Option<u32>)| {
-            return maybe_page
-                .and_then(|p| page_table.get(&p).and_then(|page| Some((index, page))));
+        .filter_map(|(index, maybe_frame): (usize, &Option<Frame>)| {
+            return maybe_frame
+                .and_then(|frame| frame_entry(processes, &frame).map(|page| (index, page)));
         })
         .collect::<Vec<(usize, &PageTableEntry)>>();
 }
 
-fn evict_least_recent(memory: &Memory, page_table: &PageTable) -> usize {
-    let mut sorted = memory_to_pages(memory, page_table);
+fn evict_least_recent(memory: &Memory, processes: &[Process]) -> usize {
+    let mut sorted = memory_to_pages(memory, processes);
 
     sorted.sort_by(|a, b| {
         return a.1.last_referenced.cmp(&b.1.last_referenced);
@@ -76,8 +164,8 @@ fn evict_least_recent(memory: &Memory, page_table: &PageTable) -> usize {
     return sorted.first().unwrap().0;
 }
 
-fn evict_fifo(memory: &Memory, page_table: &PageTable) -> usize {
-    let mut sorted = memory_to_pages(memory, page_table);
+fn evict_fifo(memory: &Memory, processes: &[Process]) -> usize {
+    let mut sorted = memory_to_pages(memory, processes);
 
     sorted.sort_by(|a, b| {
         return a.1.created_at.cmp(&b.1.created_at);
@@ -86,15 +174,100 @@ fn evict_fifo(memory: &Memory, page_table: &PageTable) -> usize {
     return sorted.first().unwrap().0;
 }
 
-pub fn evict(name: &String, memory: &Memory, page_table: &PageTable) -> usize {
+// second-chance clock sweep: starting from the persistent hand, give each
+// referenced page one more chance by clearing its bit and advancing, evicting
+// the first page whose bit is already clear. a full revolution that finds
+// everything referenced clears all bits and degenerates to fifo, so the sweep
+// is guaranteed to terminate within two revolutions.
+fn evict_clock(memory: &Memory, processes: &mut [Process], hand: &mut usize) -> usize {
+    loop {
+        let slot = *hand;
+        *hand = (*hand + 1) % memory.len();
+
+        match memory.get(slot).and_then(|maybe_frame| *maybe_frame) {
+            Some(frame) => match processes
+                .get_mut(frame.pid as usize)
+                .and_then(|process| pte_get_mut(&mut process.page_table, frame.vpn))
+            {
+                Some(entry) if entry.referenced => {
+                    entry.referenced = false;
+                }
+                // clear bit or missing entry: this slot is fair game
+                _ => return slot,
+            },
+            // empty slot (shouldn't happen once memory is full)
+            None => return slot,
+        }
+    }
+}
+
+// not-recently-used: sort each resident page into one of four classes from its
+// referenced and dirty bits and evict from the lowest non-empty class. the class
+// number orders clean before dirty at each reference level, so the disk
+// write-back counted in write_count is avoided whenever a clean page exists at
+// the lowest reference level present. an unreferenced dirty page (class 1) is
+// still chosen over a referenced clean one (class 2).
+fn evict_nru(memory: &Memory, processes: &[Process]) -> usize {
+    // class = referenced << 1 | dirty: 0 = (!ref, !dirty) ... 3 = (ref, dirty)
+    return memory_to_pages(memory, processes)
+        .into_iter()
+        .min_by_key(|(_, entry)| (entry.referenced as u8) << 1 | (entry.is_dirty as u8))
+        .unwrap()
+        .0;
+}
+
+// distance to a frame's next reference in its owning process's trace, measured
+// as the absolute position of the first occurrence at or beyond the process's
+// current step. a page that is never referenced again reports usize::MAX so it
+// sorts as the furthest-away and is evicted first.
+fn next_use_distance(processes: &[Process], frame: &Frame) -> usize {
+    let process = &processes[frame.pid as usize];
+    return match process.occurrences.get(&frame.vpn) {
+        Some(positions) => {
+            // the occurrence list is ascending, so binary-search for the first
+            // position that is not before the current step
+            let next = positions.partition_point(|&position| position < process.step);
+            match positions.get(next) {
+                Some(&position) => position,
+                None => usize::MAX,
+            }
+        }
+        None => usize::MAX,
+    };
+}
+
+// Belady's MIN: evict the resident page whose next use lies furthest in the
+// future. an unattainable lower bound on faults, useful for judging how close
+// the practical policies come to optimal.
+fn evict_opt(memory: &Memory, processes: &[Process]) -> usize {
+    return memory
+        .iter()
+        .enumerate()
+        .filter_map(|(index, maybe_frame): (usize, &Option<Frame>)| {
+            return maybe_frame.map(|frame| (index, frame));
+        })
+        .max_by_key(|(_, frame)| next_use_distance(processes, frame))
+        .unwrap()
+        .0;
+}
+
+pub fn evict(
+    name: &String,
+    memory: &Memory,
+    processes: &mut [Process],
+    hand: &mut usize,
+) -> usize {
     if let Some(n) = get_first_empty_index(&memory) {
         return n;
     }
 
     return match name.as_str() {
         "random" => evict_random(memory),
-        "lru" => evict_least_recent(memory, page_table),
-        "fifo" => evict_fifo(memory, page_table),
+        "lru" => evict_least_recent(memory, processes),
+        "fifo" => evict_fifo(memory, processes),
+        "clock" => evict_clock(memory, processes, hand),
+        "nru" => evict_nru(memory, processes),
+        "opt" => evict_opt(memory, processes),
         v => panic!("{} is not a valid eviction algorithm", v),
     };
 }
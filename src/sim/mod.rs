@@ -1,21 +1,53 @@
+use std::collections::HashMap;
 use std::fmt;
 use std::fs;
 use std::io;
 use std::io::BufRead;
 
+// default timer-tick interval (in operations) for NRU reference-bit decay
+const DEFAULT_TICK_INTERVAL: u32 = 10_000;
+
 mod evictors;
-use evictors::{evict, Memory, PageTable, PageTableEntry};
+mod tlb;
+use tlb::Tlb;
+use evictors::{
+    evict, pte_contains, pte_get_mut, pte_insert, pte_remove, Frame, Memory, PageTable,
+    MMUFLAG_EXECUTABLE, MMUFLAG_READABLE, MMUFLAG_USERMODE, MMUFLAG_WRITABLE,
+};
 
-fn read_file(path: String) -> io::Lines<io::BufReader<fs::File>> {
+// materialize a trace file into the full, ordered list of operations. OPT needs
+// the whole sequence up front so it can look ahead to each page's next use, so
+// the trace is read once here rather than streamed line by line.
+fn read_operations(path: String) -> Vec<Operation> {
     let file = match fs::File::open(&path) {
         Ok(f) => f,
         Err(e) => panic!("could not read file {} {}", path, e),
     };
 
     let reader = io::BufReader::new(file);
-    return reader.lines();
+    return reader
+        .lines()
+        .map(|line| match line {
+            Ok(line) => Operation::parse_line(line),
+            Err(e) => panic!("could not read line {:?}", e),
+        })
+        .collect();
+}
+
+// index, for each virtual page number, the positions at which it is referenced,
+// so OPT can binary-search for the first reference beyond the current step
+fn build_occurrences(operations: &[Operation]) -> HashMap<u32, Vec<usize>> {
+    let mut occurrences: HashMap<u32, Vec<usize>> = HashMap::new();
+    for (position, op) in operations.iter().enumerate() {
+        occurrences
+            .entry(op.virtual_page_number)
+            .or_insert_with(Vec::new)
+            .push(position);
+    }
+    return occurrences;
 }
 
+#[derive(Clone, Copy)]
 pub enum Op {
     R,
     W,
@@ -29,11 +61,17 @@ impl fmt::Display for Op {
     }
 }
 
+#[derive(Clone, Copy)]
 pub struct Operation {
     pub virtual_address: u32,
     pub virtual_page_number: u32,
     pub page_offset: u32,
     pub op: Op,
+    // optional permission annotation carried by the trace: a mask of the
+    // MMUFLAG_* bits the page should be mapped with. `None` leaves the entry's
+    // flags unchanged: the default (readable/writable/user) for a page never
+    // annotated, so unannotated traces behave as before.
+    pub flags: Option<u8>,
 }
 impl Operation {
     pub fn parse_line(line: String) -> Operation {
@@ -55,115 +93,355 @@ impl Operation {
                 "W" => Op::W,
                 value => panic!("unknown value: {}", value),
             },
+            // a third field, when present, spells the page's permissions as a
+            // string of r/w/x/u letters (e.g. "ru" for a read-only user page)
+            flags: values.get(2).map(|annotation| parse_flags(annotation)),
         };
     }
 }
 
-#[derive(Clone, Copy)]
+// translate a permission annotation like "rwx" or "ru" into an MMUFLAG_* mask
+fn parse_flags(annotation: &str) -> u8 {
+    let mut flags = 0;
+    for c in annotation.chars() {
+        flags |= match c {
+            'r' => MMUFLAG_READABLE,
+            'w' => MMUFLAG_WRITABLE,
+            'x' => MMUFLAG_EXECUTABLE,
+            'u' => MMUFLAG_USERMODE,
+            value => panic!("unknown permission flag: {}", value),
+        };
+    }
+    return flags;
+}
+
+// read/write/fault tallies for a single process sharing physical memory
+#[derive(Clone)]
+pub struct ProcessStats {
+    pub read_count: u32,
+    pub write_count: u32,
+    pub fault_count: u32,
+}
+impl ProcessStats {
+    pub fn new() -> Self {
+        return Self {
+            read_count: 0,
+            write_count: 0,
+            fault_count: 0,
+        };
+    }
+}
+
+#[derive(Clone)]
 pub struct SimState {
     pub total_events: u32,
     pub read_count: u32,
     pub write_count: u32,
+    pub protection_fault_count: u32,
+    pub tlb_hits: u32,
+    pub tlb_misses: u32,
+    // per-process tallies, indexed by process id
+    pub per_process: Vec<ProcessStats>,
+}
+
+// a runnable context: its own trace, page table, and TLB, identified by pid.
+// all processes compete for the single shared `Memory` held on `Sim`.
+pub struct Process {
+    pub pid: u32,
+    // the whole trace, materialized so OPT can look ahead, plus the index of the
+    // next operation to execute and a vpn -> occurrence-positions index
+    pub operations: Vec<Operation>,
+    pub occurrences: HashMap<u32, Vec<usize>>,
+    pub step: usize,
+    pub page_table: PageTable,
+    pub tlb: Tlb,
+    pub finished: bool,
 }
 
 pub struct Sim {
     pub algorithm: String,
     pub debug: bool,
     pub state: SimState,
-    pub trace: io::Lines<io::BufReader<fs::File>>,
     pub memory: Memory,
-    pub page_table: PageTable,
+    pub processes: Vec<Process>,
+    // persistent clock-hand position for the second-chance evictor
+    pub clock_hand: usize,
+    // round-robin scheduler: run `quantum` operations of `current` before switching
+    pub quantum: u32,
+    pub current: usize,
+    pub ops_in_quantum: u32,
+    // logical clock driving the periodic reference-bit decay used by NRU;
+    // every `tick_interval` operations the referenced bit of every resident
+    // page is cleared, mirroring a kernel's timer interrupt
+    pub logical_clock: u64,
+    pub tick_interval: u32,
 }
 impl Sim {
-    pub fn new(n_pages: u32, algorithm: String, trace_file: String, debug: bool) -> Self {
+    pub fn new(
+        n_pages: u32,
+        algorithm: String,
+        trace_file: String,
+        debug: bool,
+        tlb_capacity: usize,
+    ) -> Self {
+        // a single-process run is just a one-element process list whose quantum
+        // is effectively unbounded, so the scheduler never switches away from it
+        return Self::new_multi(
+            n_pages,
+            algorithm,
+            vec![trace_file],
+            debug,
+            tlb_capacity,
+            u32::MAX,
+            DEFAULT_TICK_INTERVAL,
+        );
+    }
+
+    // drive several traces concurrently against one shared physical memory,
+    // interleaving them round-robin with the given quantum
+    pub fn new_multi(
+        n_pages: u32,
+        algorithm: String,
+        trace_files: Vec<String>,
+        debug: bool,
+        tlb_capacity: usize,
+        quantum: u32,
+        tick_interval: u32,
+    ) -> Self {
         let mut memory: Memory = Memory::new();
         // 1048575 possible pages in page table in 20 bits
         // memory is full of None by default
         memory.resize_with(n_pages as usize, || None);
 
+        let processes: Vec<Process> = trace_files
+            .into_iter()
+            .enumerate()
+            .map(|(pid, trace_file)| {
+                let operations = read_operations(trace_file);
+                let occurrences = build_occurrences(&operations);
+                let finished = operations.is_empty();
+                Process {
+                    pid: pid as u32,
+                    operations,
+                    occurrences,
+                    step: 0,
+                    page_table: PageTable::new(),
+                    tlb: Tlb::new(tlb_capacity),
+                    finished,
+                }
+            })
+            .collect();
+
+        let per_process = processes.iter().map(|_| ProcessStats::new()).collect();
+
         return Self {
             algorithm,
             debug,
-            trace: read_file(trace_file),
             memory,
-            page_table: PageTable::new(),
+            clock_hand: 0,
+            quantum,
+            current: 0,
+            ops_in_quantum: 0,
+            logical_clock: 0,
+            tick_interval,
+            processes,
             state: SimState {
                 total_events: 0,
                 read_count: 0,
                 write_count: 0,
+                protection_fault_count: 0,
+                tlb_hits: 0,
+                tlb_misses: 0,
+                per_process,
             },
         };
     }
+
+    // pick the process to run this step, switching on an exhausted quantum or a
+    // finished process; returns None once every process has drained its trace
+    fn pick_process(&mut self) -> Option<usize> {
+        if self.processes.iter().all(|p| p.finished) {
+            return None;
+        }
+
+        let len = self.processes.len();
+        if self.ops_in_quantum >= self.quantum || self.processes[self.current].finished {
+            for _ in 0..len {
+                self.current = (self.current + 1) % len;
+                if !self.processes[self.current].finished {
+                    break;
+                }
+            }
+            self.ops_in_quantum = 0;
+        }
+
+        return Some(self.current);
+    }
+
+    // clear the referenced bit of every resident page, aging them so NRU can
+    // tell which pages have been touched since the last timer tick. dirty bits
+    // are left untouched: they persist until the page is evicted and written back.
+    fn clear_reference_bits(&mut self) {
+        for slot in 0..self.memory.len() {
+            if let Some(frame) = self.memory[slot] {
+                if let Some(entry) =
+                    pte_get_mut(&mut self.processes[frame.pid as usize].page_table, frame.vpn)
+                {
+                    entry.referenced = false;
+                }
+            }
+        }
+    }
 }
 impl Iterator for Sim {
     type Item = SimState;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let mut state = self.state.clone();
+        loop {
+            let pid = self.pick_process()?;
 
-        if let Some(line) = self.trace.next() as Option<io::Result<String>> {
-            if line.is_err() {
-                panic!("could not read line {:?}", line);
+            if self.processes[pid].step >= self.processes[pid].operations.len() {
+                // this process is out of operations; let the scheduler move on
+                self.processes[pid].finished = true;
+                continue;
             }
 
-            state.total_events += 1;
+            // read the current operation and advance this process's position, so
+            // the step now points at what comes next (the basis for OPT lookahead)
+            let op = self.processes[pid].operations[self.processes[pid].step];
+            self.processes[pid].step += 1;
+            self.ops_in_quantum += 1;
+
+            // advance the logical clock and, on each timer tick, age the
+            // referenced bits of all resident pages. only nru relies on this
+            // aging; running it unconditionally would periodically wipe the
+            // referenced bits the clock evictor depends on, degrading it to fifo.
+            self.logical_clock += 1;
+            if self.algorithm == "nru"
+                && self.tick_interval > 0
+                && self.logical_clock % self.tick_interval as u64 == 0
+            {
+                self.clear_reference_bits();
+            }
 
-            let op = Operation::parse_line(line.unwrap());
+            let mut state = self.state.clone();
+            state.total_events += 1;
 
             if self.debug {
                 println!(
-                    r#"Perform "{}" operation
+                    r#"process {} perform "{}" operation
           virtual address     {:#034b}
           virtual page number {:#022b}
           page offset                             {:#014b}
         "#,
-                    op.op, op.virtual_address, op.virtual_page_number, op.page_offset
+                    pid, op.op, op.virtual_address, op.virtual_page_number, op.page_offset
                 );
             }
 
             // create page table entry if it does not exist
-            if !self.page_table.contains_key(&op.virtual_page_number) {
-                self.page_table
-                    .insert(op.virtual_page_number, PageTableEntry::new());
+            if !pte_contains(&self.processes[pid].page_table, op.virtual_page_number) {
+                pte_insert(&mut self.processes[pid].page_table, op.virtual_page_number);
             }
 
-            // load from disk if not loaded
-            if !self.memory.contains(&Some(op.virtual_page_number)) {
-                // pick page to be evicted
-                let available_physical_page_index =
-                    evict(&self.algorithm, &self.memory, &self.page_table);
-
-                let available_physical_page_number: &Option<u32> =
-                    self.memory.get(available_physical_page_index).unwrap();
-
-                // save previous page
-                if let Some(physical_page_number) = available_physical_page_number {
-                    if let Some(evicted_page) = self.page_table.get_mut(physical_page_number) {
-                        if self.debug {
-                            println!(
-                                "  evict page {:?} to load page {}\n",
-                                available_physical_page_number, op.virtual_page_number
-                            );
-                        }
+            let frame = Frame {
+                // tag the frame with the owning process's id so eviction never
+                // hands a victim's frame back to another process's mapping
+                pid: self.processes[pid].pid,
+                vpn: op.virtual_page_number,
+            };
+
+            // consult this process's TLB before walking the page table or
+            // scanning memory; a hit implies the page is still resident because
+            // entries are invalidated whenever their physical slot is reassigned
+            if self.processes[pid].tlb.lookup(op.virtual_page_number).is_some() {
+                state.tlb_hits += 1;
+            } else {
+                state.tlb_misses += 1;
+
+                // load from disk if not loaded
+                let slot = if !self.memory.contains(&Some(frame)) {
+                    // pick page to be evicted
+                    let available_physical_page_index = evict(
+                        &self.algorithm,
+                        &self.memory,
+                        &mut self.processes,
+                        &mut self.clock_hand,
+                    );
 
-                        if evicted_page.is_dirty {
-                            // for simplicity: instead of resetting the entry, simply destroy it
-                            self.page_table.remove(physical_page_number);
-                            state.write_count += 1;
+                    let victim: Option<Frame> =
+                        *self.memory.get(available_physical_page_index).unwrap();
+
+                    // save previous page, billing the write back to its owner
+                    if let Some(victim) = victim {
+                        if let Some(evicted_page) = pte_get_mut(
+                            &mut self.processes[victim.pid as usize].page_table,
+                            victim.vpn,
+                        ) {
+                            if self.debug {
+                                println!(
+                                    "  evict frame {:?} to load page {} for process {}\n",
+                                    victim, op.virtual_page_number, pid
+                                );
+                            }
+
+                            if evicted_page.is_dirty {
+                                // for simplicity: instead of resetting the entry, simply destroy it
+                                pte_remove(
+                                    &mut self.processes[victim.pid as usize].page_table,
+                                    victim.vpn,
+                                );
+                                state.write_count += 1;
+                                state.per_process[victim.pid as usize].write_count += 1;
+                            }
                         }
                     }
-                }
 
-                // load from disk
-                state.read_count += 1;
+                    // load from disk
+                    state.read_count += 1;
+                    state.per_process[pid].read_count += 1;
 
-                // load into memory
-                self.memory[available_physical_page_index] = Some(op.virtual_page_number);
+                    // drop any stale translation pointing at the reused frame,
+                    // across every process's TLB
+                    for process in self.processes.iter_mut() {
+                        process.tlb.invalidate_slot(available_physical_page_index);
+                    }
+
+                    // load into memory, tagging the slot with the owning process
+                    self.memory[available_physical_page_index] = Some(frame);
+                    available_physical_page_index
+                } else {
+                    self.memory
+                        .iter()
+                        .position(|f| *f == Some(frame))
+                        .unwrap()
+                };
+
+                // cache the fresh translation for subsequent accesses
+                self.processes[pid].tlb.insert(op.virtual_page_number, slot);
             }
 
-            let entry = self.page_table.get_mut(&op.virtual_page_number).unwrap();
+            let entry =
+                pte_get_mut(&mut self.processes[pid].page_table, op.virtual_page_number).unwrap();
             entry.reference();
 
+            // adopt any permission annotation the trace carries for this page
+            if let Some(flags) = op.flags {
+                entry.flags = flags;
+            }
+
+            // enforce the page's permission bits: an access the flags forbid
+            // (e.g. a write to a read-only page) traps instead of touching memory
+            let required = match op.op {
+                Op::R => MMUFLAG_READABLE,
+                Op::W => MMUFLAG_WRITABLE,
+            };
+            if !entry.permits(required) {
+                state.protection_fault_count += 1;
+                state.per_process[pid].fault_count += 1;
+                self.state = state;
+                return Some(self.state.clone());
+            }
+
             match op.op {
                 Op::W => {
                     entry.is_dirty = true;
@@ -172,10 +450,7 @@ impl Iterator for Sim {
             }
 
             self.state = state;
-            return Some(self.state);
+            return Some(self.state.clone());
         }
-
-        // iterator is done
-        return None;
     }
 }
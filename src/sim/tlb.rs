@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+
+// a small fully-associative translation lookaside buffer caching recent
+// `virtual_page_number -> physical_slot` translations. entries are evicted with
+// their own LRU policy once the fixed capacity is exceeded, tracked with a
+// logical use counter rather than wall-clock time.
+pub struct Tlb {
+    capacity: usize,
+    clock: u64,
+    entries: HashMap<u32, (usize, u64)>,
+}
+impl Tlb {
+    pub fn new(capacity: usize) -> Self {
+        return Self {
+            capacity,
+            clock: 0,
+            entries: HashMap::new(),
+        };
+    }
+
+    // look up a translation, refreshing its recency on a hit
+    pub fn lookup(&mut self, virtual_page_number: u32) -> Option<usize> {
+        self.clock += 1;
+        let clock = self.clock;
+        return match self.entries.get_mut(&virtual_page_number) {
+            Some(entry) => {
+                entry.1 = clock;
+                Some(entry.0)
+            }
+            None => None,
+        };
+    }
+
+    // cache a translation, evicting the least-recently-used entry when full
+    pub fn insert(&mut self, virtual_page_number: u32, physical_slot: usize) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if !self.entries.contains_key(&virtual_page_number) && self.entries.len() >= self.capacity {
+            if let Some((&victim, _)) = self.entries.iter().min_by_key(|(_, (_, used))| *used) {
+                self.entries.remove(&victim);
+            }
+        }
+
+        self.clock += 1;
+        self.entries.insert(virtual_page_number, (physical_slot, self.clock));
+    }
+
+    // drop any translation pointing at a physical slot that is being reused, so
+    // a later hit can never hand back a stale frame
+    pub fn invalidate_slot(&mut self, physical_slot: usize) {
+        self.entries.retain(|_, (slot, _)| *slot != physical_slot);
+    }
+}